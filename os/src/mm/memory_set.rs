@@ -1,14 +1,14 @@
 //! Implementation of [`MapArea`] and [`MemorySet`].
 
 use super::{frame_alloc, FrameTracker};
-use super::{PTEFlags, PageTable, PageTableEntry};
+use super::{PTEFlags, PageSize, PageTable, PageTableEntry};
 use super::{PhysAddr, PhysPageNum, VirtAddr, VirtPageNum};
 use super::{StepByOne, VPNRange};
 use crate::config::{
     KERNEL_STACK_SIZE, MEMORY_END, PAGE_SIZE, TRAMPOLINE, TRAP_CONTEXT_BASE, USER_STACK_SIZE,
 };
 use crate::sync::UPSafeCell;
-use alloc::collections::BTreeMap;
+use alloc::collections::{BTreeMap, BTreeSet};
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 use core::arch::asm;
@@ -33,6 +33,10 @@ lazy_static! {
     pub static ref KERNEL_SPACE: Arc<UPSafeCell<MemorySet>> =
         Arc::new(unsafe { UPSafeCell::new(MemorySet::new_kernel()) });
 }
+/// Lowest virtual address an `mmap` is allowed to use, as in Linux/DragonOS.
+/// 挡住对 NULL 页(以及附近低地址)的映射,避免空指针解引用被意外“掩盖”。
+const MMAP_MIN_ADDR: usize = 0x10000;
+
 /// address space
 pub struct MemorySet {
     page_table: PageTable,
@@ -64,6 +68,11 @@ impl MemorySet {
             None,
         );
     }
+    /// 只登记段信息而不分配/映射任何物理页,用于惰性(按需调页)映射。
+    /// 物理页会在第一次访问触发缺页异常时由 [`Self::handle_page_fault`] 分配。
+    fn push_lazy(&mut self, map_area: MapArea) {
+        self.areas.push(map_area);
+    }
     /// 根据虚拟地址范围,分配对应的物理页
     fn push(&mut self, mut map_area: MapArea, data: Option<&[u8]>) {
         // println!("map_area:{:?}", map_area.vpn_range.get_start());
@@ -142,12 +151,14 @@ impl MemorySet {
             None,
         );
         info!("mapping physical memory");
+        // 用 2MiB 超大页映射这片大的恒等区域,省下成千上万个 4KiB 页表项与 TLB 项
         memory_set.push(
-            MapArea::new(
+            MapArea::new_huge(
                 (ekernel as usize).into(),
                 MEMORY_END.into(),
                 MapType::Identical,
                 MapPermission::R | MapPermission::W,
+                PageSize::Page2M,
             ),
             None,
         );
@@ -236,6 +247,85 @@ impl MemorySet {
             elf.header.pt2.entry_point() as usize,
         )
     }
+    /// Clone this address space copy-on-write: the child shares every `Framed`
+    /// physical page with the parent instead of deep-copying. Both parent and
+    /// child PTEs for shared pages have their `W` bit cleared (the original
+    /// writability is remembered in `cow_writable`), so the first store to such
+    /// a page traps into [`Self::handle_cow_fault`].
+    pub fn clone_cow(&mut self) -> MemorySet {
+        let mut child = Self::new_bare();
+        child.map_trampoline();
+        for area in self.areas.iter_mut() {
+            let mut new_area = MapArea::new(
+                area.vpn_range.get_start().into(),
+                area.vpn_range.get_end().into(),
+                area.map_type,
+                area.map_perm,
+            );
+            match area.map_type {
+                MapType::Identical => {
+                    // 恒等映射的内核段直接重建映射,不涉及共享帧
+                    new_area.map(&mut child.page_table);
+                }
+                MapType::Framed => {
+                    let writable = area.map_perm.contains(MapPermission::W);
+                    let ro_flags = PTEFlags::from_bits(
+                        (area.map_perm & !MapPermission::W).bits,
+                    )
+                    .unwrap();
+                    for (&vpn, frame) in area.data_frames.iter() {
+                        let ppn = frame.ppn;
+                        // 父子共享同一个 Arc<FrameTracker>
+                        new_area.data_frames.insert(vpn, Arc::clone(frame));
+                        child.page_table.map(vpn, ppn, ro_flags);
+                        if writable {
+                            // 清掉父页的 W 位并记录它原本可写
+                            self.page_table
+                                .set_pte(vpn, PageTableEntry::new(ppn, ro_flags | PTEFlags::V));
+                            area.cow_writable.insert(vpn);
+                            new_area.cow_writable.insert(vpn);
+                        }
+                    }
+                }
+            }
+            child.areas.push(new_area);
+        }
+        child
+    }
+
+    /// Resolve a store fault on a copy-on-write page. Returns `true` if `vpn`
+    /// was a COW page and was made writable again. If the frame is still shared
+    /// (`Arc::strong_count > 1`) a private copy is made; otherwise `W` is simply
+    /// restored in place.
+    pub fn handle_cow_fault(&mut self, vpn: VirtPageNum) -> bool {
+        for area in self.areas.iter_mut() {
+            if !area.cow_writable.contains(&vpn) {
+                continue;
+            }
+            let frame = area.data_frames.get(&vpn).unwrap();
+            let flags = PTEFlags::from_bits(area.map_perm.bits).unwrap() | PTEFlags::V;
+            if Arc::strong_count(frame) > 1 {
+                // 还有其它地址空间共享这个帧,复制出一份私有页
+                let old_ppn = frame.ppn;
+                let new_frame = frame_alloc().unwrap();
+                let new_ppn = new_frame.ppn;
+                new_ppn
+                    .get_bytes_array()
+                    .copy_from_slice(old_ppn.get_bytes_array());
+                area.data_frames.insert(vpn, Arc::new(new_frame));
+                self.page_table
+                    .set_pte(vpn, PageTableEntry::new(new_ppn, flags));
+            } else {
+                // 独占了,直接恢复可写
+                let ppn = frame.ppn;
+                self.page_table.set_pte(vpn, PageTableEntry::new(ppn, flags));
+            }
+            area.cow_writable.remove(&vpn);
+            return true;
+        }
+        false
+    }
+
     /// Change page table by writing satp CSR Register.
     pub fn activate(&self) {
         let satp = self.page_table.token();
@@ -298,9 +388,30 @@ impl MemorySet {
     /// - port & 0x7 = 0 (这样的内存无意义)
     /// - [start, start + len) 中存在已经被映射的页
     /// - 物理内存不足
-    pub fn mmap(&mut self, start: usize, len: usize, port: usize) -> isize {
+    /// `populate` 对应 Linux 的 `MAP_POPULATE`:为 `true` 时立即预先调入
+    /// 所有物理页(老行为),为 `false` 时只登记区域,物理页在首次访问缺页时
+    /// 由 [`Self::handle_page_fault`] 分配。
+    /// `start` 为 0 时由内核在 `MMAP_MIN_ADDR` 以上选址,成功时返回选定的基址
+    /// 而不是 0;非 0 的 `start` 低于 `MMAP_MIN_ADDR` 时返回 -1。
+    pub fn mmap(&mut self, start: usize, len: usize, port: usize, populate: bool) -> isize {
         let len = if len < 4096 { 4096 } else { len };
 
+        // start == 0 表示“由内核选地址”:在 MMAP_MIN_ADDR 以上找最低的足够大的空隙
+        let (start, kernel_chosen) = if start == 0 {
+            let pages = VirtAddr::from(len).ceil().0;
+            let min_vpn = VirtAddr::from(MMAP_MIN_ADDR).floor();
+            match self.find_free_base(min_vpn, pages) {
+                Some(base) => (base.0 * PAGE_SIZE, true),
+                None => return -1,
+            }
+        } else {
+            // 拒绝映射 MMAP_MIN_ADDR 以下的低地址(含 NULL 页)
+            if start < MMAP_MIN_ADDR {
+                return -1;
+            }
+            (start, false)
+        };
+
         let start_va: VirtAddr = start.into();
         let start_vpn: VirtPageNum = start_va.floor();
 
@@ -327,8 +438,11 @@ impl MemorySet {
         // let vpn_range = VPNRange::new(start_vpn, end_vpn);
         // vpn_range.into_iter().any(|vpn| self.areas.iter().)
         for vpn in start_vpn.0..end_vpn.0 {
+            let vpn = VirtPageNum(vpn);
             for area in &self.areas {
-                if area.data_frames.get(&VirtPageNum(vpn)).is_some() {
+                // 用 vpn_range 判重,而不是只看已物化的 data_frames:惰性登记的
+                // 区域 data_frames 为空,只检查后者会漏掉对已预留区间的重复映射。
+                if area.vpn_range.get_start() <= vpn && vpn < area.vpn_range.get_end() {
                     // debug!(
                     //     "!!!!!![start {}, start + len {}) 中存在已经被映射的页",
                     //     start, len
@@ -360,13 +474,325 @@ impl MemorySet {
         let mut permission = MapPermission::from_bits((port as u8) << 1).unwrap();
         permission.set(MapPermission::U, true);
 
-        self.insert_framed_area(start_va, end_va, permission);
+        if populate {
+            // MAP_POPULATE:立即分配并映射所有物理页(老行为,测例依赖它)
+            self.insert_framed_area(start_va, end_va, permission);
+        } else {
+            // 惰性模式:只登记区域,物理页在首次访问缺页时再分配
+            self.push_lazy(MapArea::new(start_va, end_va, MapType::Framed, permission));
+        }
         // for area in &mut self.areas {
         //     debug!("after mmap {:?}", area.data_frames);
         // }
+        // 内核自行选址时返回选定的基址,否则沿用原来的成功返回 0
+        if kernel_chosen {
+            start as isize
+        } else {
+            0
+        }
+    }
+
+    /// Give advice about an already-reserved range, like `madvise(2)`.
+    /// `MADV_DONTNEED`(4)释放 `[start, start+len)` 背后的物理页但保留 `MapArea`
+    /// 记录,之后再访问会经惰性缺页处理重新映射一张清零页;`MADV_WILLNEED`(3)
+    /// 预先把范围内尚未映射的页调入。范围必须落在已登记的段内且按页对齐,否则
+    /// 返回 -1。
+    #[allow(unused)]
+    pub fn madvise(&mut self, start: usize, len: usize, advice: usize) -> isize {
+        const MADV_WILLNEED: usize = 3;
+        const MADV_DONTNEED: usize = 4;
+
+        let start_va: VirtAddr = start.into();
+        if start_va.page_offset() != 0 {
+            return -1;
+        }
+        let len = if len < PAGE_SIZE { PAGE_SIZE } else { len };
+        let start_vpn = start_va.floor();
+        let end_vpn = VirtAddr::from(start + len).ceil();
+
+        // 范围内每个页都必须落在某个已登记的 Framed 段内
+        for vpn in start_vpn.0..end_vpn.0 {
+            let vpn = VirtPageNum(vpn);
+            if !self.areas.iter().any(|a| {
+                a.map_type == MapType::Framed
+                    && a.vpn_range.get_start() <= vpn
+                    && vpn < a.vpn_range.get_end()
+            }) {
+                return -1;
+            }
+        }
+
+        match advice {
+            MADV_DONTNEED => {
+                for vpn in start_vpn.0..end_vpn.0 {
+                    let vpn = VirtPageNum(vpn);
+                    for area in self.areas.iter_mut() {
+                        if area.data_frames.get(&vpn).is_some() {
+                            // 释放物理页,但保留段记录,下次访问重新缺页
+                            area.unmap_one(&mut self.page_table, vpn);
+                            break;
+                        }
+                    }
+                }
+                0
+            }
+            MADV_WILLNEED => {
+                for vpn in start_vpn.0..end_vpn.0 {
+                    let vpn = VirtPageNum(vpn);
+                    for area in self.areas.iter_mut() {
+                        if area.map_type == MapType::Framed
+                            && area.vpn_range.get_start() <= vpn
+                            && vpn < area.vpn_range.get_end()
+                            && area.data_frames.get(&vpn).is_none()
+                        {
+                            area.map_one(&mut self.page_table, vpn);
+                            break;
+                        }
+                    }
+                }
+                0
+            }
+            _ => -1,
+        }
+    }
+
+    /// Scan `areas` for the lowest free VPN gap (at or above `min`) large enough
+    /// for `pages` pages. Returns the base VPN of the gap, or `None` if the
+    /// address space is too fragmented.
+    fn find_free_base(&self, min: VirtPageNum, pages: usize) -> Option<VirtPageNum> {
+        let mut ranges: Vec<(usize, usize)> = self
+            .areas
+            .iter()
+            .map(|a| (a.vpn_range.get_start().0, a.vpn_range.get_end().0))
+            .collect();
+        ranges.sort_unstable();
+        let mut candidate = min.0;
+        for (s, e) in ranges {
+            if e <= candidate {
+                continue;
+            }
+            if s >= candidate + pages {
+                break;
+            }
+            candidate = candidate.max(e);
+        }
+        // 空隙不能越过跳板页所在的地址空间顶端
+        if candidate + pages > VirtAddr::from(TRAMPOLINE).floor().0 {
+            return None;
+        }
+        Some(VirtPageNum(candidate))
+    }
+
+    /// Resize and optionally relocate an existing mapping, like `mremap(2)`.
+    /// 缩小时释放尾部的页;原地增长时,若相邻地址范围空闲则映射多出来的页;
+    /// 若被占用且 `may_move` 为真,则在别处另分配一块 `new_len` 的 framed 区域,
+    /// 把已有的 `FrameTracker` 移动过去(不复制页内容)、在新基址重新映射、
+    /// 解除旧映射,并返回新基址。无法增长且 `may_move` 为假,或源范围不是单一
+    /// 连续映射时返回 -1。
+    #[allow(unused)]
+    pub fn mremap(
+        &mut self,
+        old_start: usize,
+        old_len: usize,
+        new_len: usize,
+        may_move: bool,
+    ) -> isize {
+        let old_start_va: VirtAddr = old_start.into();
+        if old_start_va.page_offset() != 0 {
+            return -1;
+        }
+        let old_start_vpn = old_start_va.floor();
+        let idx = match self
+            .areas
+            .iter()
+            .position(|a| a.vpn_range.get_start() == old_start_vpn)
+        {
+            Some(idx) => idx,
+            None => return -1,
+        };
+        let old_end_vpn = self.areas[idx].vpn_range.get_end();
+        // 源范围必须恰好是这一个连续段
+        if old_end_vpn != VirtAddr::from(old_start + old_len).ceil() {
+            return -1;
+        }
+        let new_end_vpn = VirtAddr::from(old_start + new_len).ceil();
+
+        if new_end_vpn.0 <= old_start_vpn.0 {
+            return -1;
+        }
+        if new_end_vpn == old_end_vpn {
+            return old_start as isize;
+        }
+        if new_end_vpn.0 < old_end_vpn.0 {
+            // 收缩:释放尾部页
+            self.areas[idx].shrink_to(&mut self.page_table, new_end_vpn);
+            return old_start as isize;
+        }
+
+        // 需要增长:检查相邻 [old_end, new_end) 是否空闲
+        let grow_free = (old_end_vpn.0..new_end_vpn.0).all(|vpn| {
+            let vpn = VirtPageNum(vpn);
+            !self
+                .areas
+                .iter()
+                .any(|a| a.vpn_range.get_start() <= vpn && vpn < a.vpn_range.get_end())
+        });
+        if grow_free {
+            self.areas[idx].append_to(&mut self.page_table, new_end_vpn);
+            return old_start as isize;
+        }
+        if !may_move {
+            return -1;
+        }
+
+        // 搬到别处:另找一块足够大的空闲区域,移动已有帧过去
+        let new_pages = new_end_vpn.0 - old_start_vpn.0;
+        let base = match self.find_free_base(old_start_vpn, new_pages) {
+            Some(base) => base,
+            None => return -1,
+        };
+        let mut old = self.areas.remove(idx);
+        let flags = PTEFlags::from_bits(old.map_perm.bits).unwrap();
+        let base_va: VirtAddr = base.into();
+        let mut new_area = MapArea::new(
+            base_va,
+            VirtAddr::from(base.0 * PAGE_SIZE + new_len),
+            MapType::Framed,
+            old.map_perm,
+        );
+        let old_pages = old_end_vpn.0 - old_start_vpn.0;
+        for i in 0..old_pages {
+            let old_vpn = VirtPageNum(old_start_vpn.0 + i);
+            let new_vpn = VirtPageNum(base.0 + i);
+            // 惰性登记、尚未调入的页没有帧可搬,留给新段的缺页处理
+            if let Some(frame) = old.data_frames.remove(&old_vpn) {
+                let ppn = frame.ppn;
+                self.page_table.unmap(old_vpn);
+                self.page_table.map(new_vpn, ppn, flags);
+                new_area.data_frames.insert(new_vpn, frame);
+            }
+        }
+        // 增长出来的新页分配全新的帧
+        for i in old_pages..new_pages {
+            new_area.map_one(&mut self.page_table, VirtPageNum(base.0 + i));
+        }
+        self.areas.push(new_area);
+        (base.0 * PAGE_SIZE) as isize
+    }
+
+    /// Change the protection of an already-mapped range, like `mprotect(2)`.
+    /// 校验对齐和 `port` 不变式(同 `mmap`),确认 `[start, start+len)` 中每个
+    /// 虚拟页都已映射,然后改写每个 PTE 的 R/W/X 位(保留 `U`),同时更新覆盖
+    /// 它们的 `MapArea::map_perm`。当请求范围只覆盖某个段的一部分时,把该段拆成
+    /// 两/三段,使未受影响的部分保留旧权限。成功返回 0,参数非法返回 -1。
+    pub fn mprotect(&mut self, start: usize, len: usize, port: usize) -> isize {
+        let start_va: VirtAddr = start.into();
+        if start_va.page_offset() != 0 {
+            return -1;
+        }
+        if port & !0x7 != 0 || port & 0x7 == 0 {
+            return -1;
+        }
+        let len = if len < PAGE_SIZE { PAGE_SIZE } else { len };
+        let start_vpn: VirtPageNum = start_va.floor();
+        let end_vpn: VirtPageNum = VirtAddr::from(start + len).ceil();
+
+        // 确认每个页都已被映射
+        for vpn in start_vpn.0..end_vpn.0 {
+            let vpn = VirtPageNum(vpn);
+            if !self
+                .areas
+                .iter()
+                .any(|area| area.data_frames.get(&vpn).is_some())
+            {
+                return -1;
+            }
+        }
+
+        let mut new_perm = MapPermission::from_bits((port as u8) << 1).unwrap();
+        new_perm.set(MapPermission::U, true);
+        let new_flags = PTEFlags::from_bits(new_perm.bits).unwrap() | PTEFlags::V;
+
+        let old_areas = core::mem::take(&mut self.areas);
+        let mut result: Vec<MapArea> = Vec::new();
+        for mut area in old_areas {
+            let a_start = area.vpn_range.get_start();
+            let a_end = area.vpn_range.get_end();
+            let lo = a_start.max(start_vpn);
+            let hi = a_end.min(end_vpn);
+            if lo >= hi {
+                // 不相交
+                result.push(area);
+                continue;
+            }
+            // 把 [a_start, lo) 前缀切出去,保留旧权限
+            if lo > a_start {
+                let mid = area.split_off(lo);
+                result.push(area);
+                area = mid;
+            }
+            // 把 [hi, a_end) 后缀切出去,保留旧权限
+            let tail = if hi < area.vpn_range.get_end() {
+                Some(area.split_off(hi))
+            } else {
+                None
+            };
+            // 现在 area 恰好是 [lo, hi),改写权限并重新下发 PTE
+            area.map_perm = new_perm;
+            for vpn in area.vpn_range {
+                let ppn = self.page_table.translate(vpn).unwrap().ppn();
+                self.page_table
+                    .set_pte(vpn, PageTableEntry::new(ppn, new_flags));
+            }
+            result.push(area);
+            if let Some(tail) = tail {
+                result.push(tail);
+            }
+        }
+        self.areas = result;
         0
     }
 
+    /// Handle a load/store/instruction page fault on a lazily-mapped region.
+    /// 找到 `vpn` 所属的已登记 `Framed` 段,校验本次访问类型 `access_kind`
+    /// 与段权限 `map_perm` 相符,然后为该虚拟页分配一个清零的物理页并建立映射
+    /// ([`PageTable::map`] 会顺带刷新该地址的 TLB)。返回 `Ok(())` 表示已处理;
+    /// 若该地址不在任何惰性段内或访问类型越权,则返回 `Err(())`,调用方据此
+    /// 杀死进程。`access_kind` 用 [`MapPermission`] 的 `R`/`W`/`X` 表示。
+    pub fn handle_page_fault(
+        &mut self,
+        vpn: VirtPageNum,
+        access_kind: MapPermission,
+    ) -> Result<(), ()> {
+        // 先看是不是被换出的页:是则换回,并把恢复的帧交给所属段持有。
+        if let Some(frame) = super::swap::try_swap_in(&mut self.page_table, vpn) {
+            for area in self.areas.iter_mut() {
+                if area.map_type == MapType::Framed
+                    && area.vpn_range.get_start() <= vpn
+                    && vpn < area.vpn_range.get_end()
+                {
+                    area.data_frames.insert(vpn, Arc::new(frame));
+                    return Ok(());
+                }
+            }
+            return Ok(());
+        }
+        for area in self.areas.iter_mut() {
+            if area.map_type == MapType::Framed
+                && area.vpn_range.get_start() <= vpn
+                && vpn < area.vpn_range.get_end()
+                && area.data_frames.get(&vpn).is_none()
+            {
+                if !area.map_perm.contains(access_kind) {
+                    return Err(());
+                }
+                area.map_one(&mut self.page_table, vpn);
+                return Ok(());
+            }
+        }
+        Err(())
+    }
+
     /// syscall ID：215
     ///
     ///取消到 [start, start + len) 虚存的映射
@@ -416,12 +842,21 @@ impl MemorySet {
         //     }
         // }
         for vpn in start_vpn.0..end_vpn.0 {
+            let vpn = VirtPageNum(vpn);
             let mut unmap_success = false;
             for area in &mut self.areas {
                 // debug!("{:?}", area.data_frames);
-                if area.data_frames.get(&VirtPageNum(vpn)).is_some() {
+                if area.data_frames.get(&vpn).is_some() {
+                    // 已经物化的页:释放物理页并清 PTE
+                    unmap_success = true;
+                    area.unmap_one(&mut self.page_table, vpn);
+                    break;
+                } else if area.map_type == MapType::Framed
+                    && area.vpn_range.get_start() <= vpn
+                    && vpn < area.vpn_range.get_end()
+                {
+                    // 惰性登记但尚未调入的页:没有物理页可释放,直接算作成功
                     unmap_success = true;
-                    area.unmap_one(&mut self.page_table, VirtPageNum(vpn));
                     break;
                 }
             }
@@ -434,6 +869,22 @@ impl MemorySet {
                 return -1;
             }
         }
+        // 从各 Framed 段的登记范围里切掉 [start_vpn, end_vpn),惰性页之后不会再被调入
+        for mut area in core::mem::take(&mut self.areas) {
+            let lo = area.vpn_range.get_start().max(start_vpn);
+            let hi = area.vpn_range.get_end().min(end_vpn);
+            if area.map_type != MapType::Framed || lo >= hi {
+                self.areas.push(area);
+                continue;
+            }
+            if lo > area.vpn_range.get_start() {
+                let mid = area.split_off(lo);
+                self.areas.push(core::mem::replace(&mut area, mid));
+            }
+            if hi < area.vpn_range.get_end() {
+                self.areas.push(area.split_off(hi));
+            }
+        }
 
         // // 检查虚拟地址是否合法
         // if self
@@ -456,9 +907,15 @@ impl MemorySet {
 /// MapArea 翻译成段比较好
 pub struct MapArea {
     vpn_range: VPNRange,
-    data_frames: BTreeMap<VirtPageNum, FrameTracker>,
+    // 物理页用 Arc 引用计数,使同一帧能被父子地址空间共享(写时复制)
+    data_frames: BTreeMap<VirtPageNum, Arc<FrameTracker>>,
     map_type: MapType,
     map_perm: MapPermission,
+    // 该段使用的页大小:普通 4KiB 或 2MiB/1GiB 超大页
+    page_size: PageSize,
+    // 写时复制期间被临时清掉 W 位的页,这里记录它们原本是可写的,
+    // 以便缺页时恢复 W 位。
+    cow_writable: BTreeSet<VirtPageNum>,
 }
 
 impl MapArea {
@@ -476,8 +933,41 @@ impl MapArea {
             data_frames: BTreeMap::new(),
             map_type,
             map_perm,
+            page_size: PageSize::Page4K,
+            cow_writable: BTreeSet::new(),
         }
     }
+    /// Like [`Self::new`] but maps the region with a larger Sv39 page size where
+    /// the alignment allows, falling back to 4KiB at the unaligned edges.
+    #[allow(unused)]
+    pub fn new_huge(
+        start_va: VirtAddr,
+        end_va: VirtAddr,
+        map_type: MapType,
+        map_perm: MapPermission,
+        page_size: PageSize,
+    ) -> Self {
+        let mut area = Self::new(start_va, end_va, map_type, map_perm);
+        area.page_size = page_size;
+        area
+    }
+    /// Split the area at `at`, keeping `[start, at)` in `self` and returning the
+    /// tail `[at, end)` as a new area with the same metadata. The backing
+    /// `FrameTracker`s (and any COW bookkeeping) are moved to the matching half.
+    pub fn split_off(&mut self, at: VirtPageNum) -> MapArea {
+        let tail_frames = self.data_frames.split_off(&at);
+        let tail_cow = self.cow_writable.split_off(&at);
+        let tail = MapArea {
+            vpn_range: VPNRange::new(at, self.vpn_range.get_end()),
+            data_frames: tail_frames,
+            map_type: self.map_type,
+            map_perm: self.map_perm,
+            page_size: self.page_size,
+            cow_writable: tail_cow,
+        };
+        self.vpn_range = VPNRange::new(self.vpn_range.get_start(), at);
+        tail
+    }
     // pub fn intersects(&self, start: VirtPageNum, end: VirtPageNum) -> bool {
     //     self.vpn_range.intersects(&VPNRange::new(start, end))
     // }
@@ -492,12 +982,16 @@ impl MapArea {
             MapType::Framed => {
                 let frame = frame_alloc().unwrap();
                 ppn = frame.ppn;
-                self.data_frames.insert(vpn, frame);
+                self.data_frames.insert(vpn, Arc::new(frame));
             }
         }
         // println!("ppn={}", ppn.0);
         let pte_flags = PTEFlags::from_bits(self.map_perm.bits).unwrap();
         page_table.map(vpn, ppn, pte_flags);
+        // 用户 framed 页登记进回收器的 clock 环,空闲帧不足时可被换出
+        if self.map_type == MapType::Framed {
+            super::swap::track_page(page_table.token(), vpn);
+        }
         // println!("after map");
     }
     #[allow(unused)]
@@ -511,9 +1005,37 @@ impl MapArea {
     }
     /// 将页表的所有虚拟页号,分配物理页号,并匹配
     pub fn map(&mut self, page_table: &mut PageTable) {
-        for vpn in self.vpn_range {
-            // debug!("vpn:{}", vpn.0);
-            self.map_one(page_table, vpn);
+        if self.page_size == PageSize::Page4K {
+            for vpn in self.vpn_range {
+                // debug!("vpn:{}", vpn.0);
+                self.map_one(page_table, vpn);
+            }
+            return;
+        }
+        // 超大页:在自然对齐且剩余范围足够大的地方写一个高层叶子 PTE,
+        // 边缘对不齐的部分退回 4KiB。目前只有恒等映射(ppn == vpn)能直接
+        // 使用超大页,Framed 段的连续物理帧分配尚不支持,退回 4KiB。
+        let stride = self.page_size.align();
+        let start = self.vpn_range.get_start().0;
+        let end = self.vpn_range.get_end().0;
+        let mut cur = start;
+        while cur < end {
+            if self.map_type == MapType::Identical
+                && cur % stride == 0
+                && cur + stride <= end
+            {
+                let flags = PTEFlags::from_bits(self.map_perm.bits).unwrap();
+                page_table.map_huge(
+                    VirtPageNum(cur),
+                    PhysPageNum(cur),
+                    flags,
+                    self.page_size,
+                );
+                cur += stride;
+            } else {
+                self.map_one(page_table, VirtPageNum(cur));
+                cur += 1;
+            }
         }
     }
     #[allow(unused)]
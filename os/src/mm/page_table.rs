@@ -1,9 +1,13 @@
 //! Implementation of [`PageTableEntry`] and [`PageTable`].
 
 use super::{frame_alloc, FrameTracker, PhysPageNum, StepByOne, VirtAddr, VirtPageNum};
+use alloc::string::String;
 use alloc::vec;
 use alloc::vec::Vec;
 use bitflags::*;
+use core::arch::asm;
+use core::mem::size_of;
+use riscv::register::satp;
 
 bitflags! {
     /// page table entry flags
@@ -65,6 +69,42 @@ impl PageTableEntry {
     pub fn executable(&self) -> bool {
         (self.flags() & PTEFlags::X) != PTEFlags::empty()
     }
+    /// 叶子页表条目:R/W/X 不全为 0 时该 PTE 直接指向物理页(可能是大页),
+    /// 否则只是指向下一级页表。
+    pub fn is_leaf(&self) -> bool {
+        (self.flags() & (PTEFlags::R | PTEFlags::W | PTEFlags::X)) != PTEFlags::empty()
+    }
+}
+
+/// Sv39 page sizes: a leaf PTE may live at level 2 (4KiB), level 1 (2MiB)
+/// or level 0 (1GiB).
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum PageSize {
+    /// 普通 4KiB 页,叶子在第 2 级
+    Page4K,
+    /// 2MiB 大页,叶子在第 1 级
+    Page2M,
+    /// 1GiB 大页,叶子在第 0 级
+    Page1G,
+}
+
+impl PageSize {
+    /// 叶子 PTE 所在的页表级数(0 为根)
+    pub fn leaf_level(&self) -> usize {
+        match self {
+            PageSize::Page4K => 2,
+            PageSize::Page2M => 1,
+            PageSize::Page1G => 0,
+        }
+    }
+    /// 该页大小对应的页号对齐粒度(以 4KiB 页为单位)
+    pub fn align(&self) -> usize {
+        match self {
+            PageSize::Page4K => 1,
+            PageSize::Page2M => 512,
+            PageSize::Page1G => 512 * 512,
+        }
+    }
 }
 
 /// page table structure
@@ -119,6 +159,12 @@ impl PageTable {
                 result = Some(pte);
                 break;
             }
+            // 中间级碰到叶子 PTE 说明落在一个超大页内,提前返回该条目,
+            // 否则会把超大页的基址物理页误当成下一级页表写入,破坏物理内存。
+            if pte.is_valid() && pte.is_leaf() {
+                result = Some(pte);
+                break;
+            }
             if !pte.is_valid() {
                 let frame = frame_alloc().unwrap();
                 *pte = PageTableEntry::new(frame.ppn, PTEFlags::V);
@@ -144,10 +190,55 @@ impl PageTable {
             if !pte.is_valid() {
                 return None;
             }
+            // 中间级碰到叶子 PTE 说明这是一个大页,提前返回该条目,
+            // 剩余的索引位由调用方当作页内偏移处理。
+            if pte.is_leaf() {
+                result = Some(pte);
+                break;
+            }
             ppn = pte.ppn();
         }
         result
     }
+    /// Map a naturally aligned superpage (2MiB / 1GiB) by writing a leaf PTE
+    /// at an intermediate tree level instead of descending to level 2.
+    #[allow(unused)]
+    pub fn map_huge(
+        &mut self,
+        vpn: VirtPageNum,
+        ppn: PhysPageNum,
+        flags: PTEFlags,
+        size: PageSize,
+    ) {
+        let align = size.align();
+        assert_eq!(vpn.0 % align, 0, "vpn {:?} not aligned for {:?}", vpn, size);
+        assert_eq!(ppn.0 % align, 0, "ppn {:?} not aligned for {:?}", ppn, size);
+        let idxs = vpn.indexes();
+        let leaf_level = size.leaf_level();
+        let mut p = self.root_ppn;
+        for (i, idx) in idxs.iter().enumerate() {
+            let pte = &mut p.get_pte_array()[*idx];
+            if i == leaf_level {
+                assert!(!pte.is_valid(), "vpn {:?} overlaps existing mapping", vpn);
+                *pte = PageTableEntry::new(ppn, flags | PTEFlags::V);
+                break;
+            }
+            // 不允许大页落在已被更高一级的叶子 PTE(更大的超大页)覆盖的区域上;
+            // 已细分为下一级页表的区域由上面叶子级的 `is_valid` 断言拒绝
+            assert!(
+                !pte.is_leaf(),
+                "vpn {:?} overlaps existing larger superpage",
+                vpn
+            );
+            if !pte.is_valid() {
+                let frame = frame_alloc().unwrap();
+                *pte = PageTableEntry::new(frame.ppn, PTEFlags::V);
+                self.frames.push(frame);
+            }
+            p = pte.ppn();
+        }
+        self.flush(vpn);
+    }
     /// set the map between virtual page number and physical page number
     #[allow(unused)]
     pub fn map(&mut self, vpn: VirtPageNum, ppn: PhysPageNum, flags: PTEFlags) {
@@ -159,6 +250,8 @@ impl PageTable {
         assert!(!pte.is_valid(), "vpn {:?} is mapped before mapping", vpn);
         // 让查到的页表条目,它的值改成要绑定的物理页数
         *pte = PageTableEntry::new(ppn, flags | PTEFlags::V);
+        // 修改完 PTE 后 MMU 的 TLB 里可能还留着旧翻译,必须刷新
+        self.flush(vpn);
     }
     /// remove the map between virtual page number and physical page number
     #[allow(unused)]
@@ -166,11 +259,89 @@ impl PageTable {
         let pte = self.find_pte(vpn).unwrap();
         assert!(pte.is_valid(), "vpn {:?} is invalid before unmapping", vpn);
         *pte = PageTableEntry::empty();
+        self.flush(vpn);
+    }
+    /// Invalidate the TLB entry for a single virtual page by emitting
+    /// `sfence.vma rs1, x0` with rs1 = vpn << 12.
+    /// 只有当被修改的页表正是当前 satp 指向的活动页表时才需要刷新;
+    /// 内核编辑的是尚未激活的用户页表,这时刷新没有意义,直接跳过以免白白刷新。
+    pub fn flush(&self, vpn: VirtPageNum) {
+        if self.token() != satp::read().bits() {
+            return;
+        }
+        let va: VirtAddr = vpn.into();
+        unsafe {
+            asm!("sfence.vma {}, x0", in(reg) va.0);
+        }
+    }
+    /// Invalidate the entire TLB by emitting a bare `sfence.vma`.
+    #[allow(unused)]
+    pub fn flush_all(&self) {
+        unsafe {
+            asm!("sfence.vma");
+        }
     }
     /// get the page table entry from the virtual page number
+    ///
+    /// 当翻译的地址落在超大页内时,叶子 PTE 存的是超大页的基址物理页号,
+    /// 这里把页内剩余的 4KiB 偏移位折算进去,返回等价的 4KiB 粒度条目。
     pub fn translate(&self, vpn: VirtPageNum) -> Option<PageTableEntry> {
+        let idxs = vpn.indexes();
+        let mut ppn = self.root_ppn;
+        for (i, idx) in idxs.iter().enumerate() {
+            let pte = ppn.get_pte_array()[*idx];
+            if !pte.is_valid() {
+                return None;
+            }
+            if i == 2 {
+                return Some(pte);
+            }
+            if pte.is_leaf() {
+                // 剩余索引位数:第 i 级(0/1)到第 2 级,每级 9 位
+                let shift = (2 - i) * 9;
+                let offset = vpn.0 & ((1usize << shift) - 1);
+                let real_ppn = PhysPageNum(pte.ppn().0 + offset);
+                return Some(PageTableEntry::new(real_ppn, pte.flags()));
+            }
+            ppn = pte.ppn();
+        }
+        None
+    }
+    /// Return a copy of the leaf PTE even when it is invalid, unlike
+    /// [`Self::translate`] which returns `None` for an invalid entry. Needed to
+    /// recognise a "swapped-out" encoding whose `V` bit has been cleared.
+    pub fn translate_raw(&self, vpn: VirtPageNum) -> Option<PageTableEntry> {
         self.find_pte(vpn).map(|pte| *pte)
     }
+    /// Query the `A` (accessed) bit of a mapped page.
+    pub fn accessed(&self, vpn: VirtPageNum) -> bool {
+        self.find_pte(vpn)
+            .map(|pte| (pte.flags() & PTEFlags::A) != PTEFlags::empty())
+            .unwrap_or(false)
+    }
+    /// Query the `D` (dirty) bit of a mapped page.
+    pub fn dirty(&self, vpn: VirtPageNum) -> bool {
+        self.find_pte(vpn)
+            .map(|pte| (pte.flags() & PTEFlags::D) != PTEFlags::empty())
+            .unwrap_or(false)
+    }
+    /// Clear the `A` bit so the clock scanner can tell whether the page is
+    /// touched again before the next sweep (second-chance).
+    pub fn clear_accessed(&self, vpn: VirtPageNum) {
+        if let Some(pte) = self.find_pte(vpn) {
+            let flags = pte.flags() & !PTEFlags::A;
+            *pte = PageTableEntry::new(pte.ppn(), flags);
+            self.flush(vpn);
+        }
+    }
+    /// Overwrite a PTE in place with the given ppn/flags, e.g. to install a
+    /// "swapped-out" encoding or restore a page after swap-in.
+    pub fn set_pte(&mut self, vpn: VirtPageNum, pte: PageTableEntry) {
+        if let Some(slot) = self.find_pte_create(vpn) {
+            *slot = pte;
+            self.flush(vpn);
+        }
+    }
     /// get the token from the page table
     /// satp高4位设置为8,低44位是物理页号
     pub fn token(&self) -> usize {
@@ -213,3 +384,123 @@ pub fn translated_byte_buffer(token: usize, ptr: *const u8, len: usize) -> Vec<&
     }
     v
 }
+
+/// Read a NUL-terminated C string out of user space page by page, stopping at
+/// the first `\0`. Used for file paths / argv passed as `*const u8`.
+pub fn translated_str(token: usize, ptr: *const u8) -> String {
+    let page_table = PageTable::from_token(token);
+    let mut string = String::new();
+    let mut va = ptr as usize;
+    loop {
+        let start_va = VirtAddr::from(va);
+        let ppn = page_table.translate(start_va.floor()).unwrap().ppn();
+        let ch: u8 = ppn.get_bytes_array()[start_va.page_offset()];
+        if ch == 0 {
+            break;
+        }
+        string.push(ch as char);
+        va += 1;
+    }
+    string
+}
+
+/// Borrow a user struct that lies within a single page as `&T`.
+pub fn translated_ref<T>(token: usize, ptr: *const T) -> &'static T {
+    let buffers = translated_byte_buffer(token, ptr as *const u8, size_of::<T>());
+    assert_eq!(buffers.len(), 1, "translated_ref: struct spans pages");
+    unsafe { &*(buffers[0].as_ptr() as *const T) }
+}
+
+/// Borrow a user struct that lies within a single page as `&mut T`.
+pub fn translated_refmut<T>(token: usize, ptr: *mut T) -> &'static mut T {
+    let buffers = translated_byte_buffer(token, ptr as *const u8, size_of::<T>());
+    assert_eq!(buffers.len(), 1, "translated_refmut: struct spans pages");
+    unsafe { &mut *(buffers[0].as_mut_ptr() as *mut T) }
+}
+
+/// A handle onto a user buffer that may be split across several physical pages.
+/// 包装 [`translated_byte_buffer`] 的结果,提供跨页写入和按字节迭代。
+pub struct UserBuffer {
+    /// the per-page mutable slices the buffer is split into
+    pub buffers: Vec<&'static mut [u8]>,
+}
+
+impl UserBuffer {
+    /// Create a buffer wrapper from translated page slices.
+    pub fn new(buffers: Vec<&'static mut [u8]>) -> Self {
+        Self { buffers }
+    }
+    /// Total length in bytes across all pages.
+    pub fn len(&self) -> usize {
+        self.buffers.iter().map(|b| b.len()).sum()
+    }
+    /// Whether the buffer is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// Copy `data` into the user buffer, crossing page boundaries as needed.
+    /// 最多写入 `data.len()` 与缓冲区长度的较小值。
+    pub fn write(&self, data: &[u8]) -> usize {
+        let mut copied = 0;
+        for buffer in self.buffers.iter() {
+            if copied >= data.len() {
+                break;
+            }
+            let n = buffer.len().min(data.len() - copied);
+            // SAFETY: 每个切片都指向当前地址空间翻译得到的物理页
+            unsafe {
+                core::ptr::copy_nonoverlapping(
+                    data.as_ptr().add(copied),
+                    buffer.as_ptr() as *mut u8,
+                    n,
+                );
+            }
+            copied += n;
+        }
+        copied
+    }
+}
+
+impl IntoIterator for UserBuffer {
+    type Item = &'static mut [u8];
+    type IntoIter = alloc::vec::IntoIter<Self::Item>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.buffers.into_iter()
+    }
+}
+
+/// Like [`translated_byte_buffer`] but validates every page before handing out
+/// a slice: the PTE must be valid, carry the `U` bit, and satisfy `required`
+/// (e.g. `W` for a destination written by the kernel). On any violation it
+/// returns `Err(-1)` instead of unwrapping, so a user passing a bad pointer
+/// gets an error code rather than crashing the kernel.
+pub fn translated_byte_buffer_checked(
+    token: usize,
+    ptr: *const u8,
+    len: usize,
+    required: PTEFlags,
+) -> Result<Vec<&'static mut [u8]>, isize> {
+    let page_table = PageTable::from_token(token);
+    let mut start = ptr as usize;
+    let end = start + len;
+    let mut v = Vec::new();
+    while start < end {
+        let start_va = VirtAddr::from(start);
+        let mut vpn = start_va.floor();
+        let pte = page_table.translate(vpn).ok_or(-1isize)?;
+        if !pte.is_valid() || (pte.flags() & (PTEFlags::U | required)) != (PTEFlags::U | required) {
+            return Err(-1);
+        }
+        let ppn = pte.ppn();
+        vpn.step();
+        let mut end_va: VirtAddr = vpn.into();
+        end_va = end_va.min(VirtAddr::from(end));
+        if end_va.page_offset() == 0 {
+            v.push(&mut ppn.get_bytes_array()[start_va.page_offset()..]);
+        } else {
+            v.push(&mut ppn.get_bytes_array()[start_va.page_offset()..end_va.page_offset()]);
+        }
+        start = end_va.into();
+    }
+    Ok(v)
+}
@@ -0,0 +1,174 @@
+//! Accessed/Dirty-bit based page reclamation with a simple in-kernel swap area.
+//!
+//! 物理内存被固定在 8MiB 的区域里,很快就会被大的 `mmap` 用光。这里参考
+//! DragonOS 的页回收器实现一个 clock(second-chance)扫描器:当空闲页帧不足
+//! 时在已映射的用户页里找一个受害者换出——脏页写入内核内的交换区,PTE 改写成
+//! 一个无效的“已换出”编码(高位存交换槽号),物理页用 [`frame_dealloc`] 释放;
+//! 之后对该页缺页时再分配物理页、从交换槽读回并恢复映射。这样固定的物理区域就
+//! 变成了可超额分配的内存系统。
+
+use super::{frame_alloc, frame_dealloc, FrameTracker, PTEFlags, PageTable, PageTableEntry, PhysPageNum};
+use super::VirtPageNum;
+use crate::config::PAGE_SIZE;
+use crate::sync::UPSafeCell;
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::vec;
+use alloc::vec::Vec;
+use lazy_static::*;
+
+/// “已换出” PTE 的低位标记:V 清零(对 MMU 无效),但用一个保留位区分它
+/// 只是尚未映射还是被换出。交换槽号存在 bit 10 以上(原本放物理页号的位置)。
+const PTE_SWAPPED: usize = 1 << 8;
+/// 换出编码中的“无后备数据”标记:干净页换出时不占用交换槽,换回时直接给清零页。
+const PTE_SWAP_ZERO: usize = 1 << 9;
+
+lazy_static! {
+    /// 全局页回收器
+    pub static ref SWAPPER: UPSafeCell<Swapper> = unsafe { UPSafeCell::new(Swapper::new()) };
+}
+
+/// One resident user page tracked by the clock.
+#[derive(Copy, Clone)]
+struct ClockEntry {
+    token: usize,
+    vpn: VirtPageNum,
+}
+
+/// The reclamation subsystem: a clock ring of resident pages plus the swap area.
+pub struct Swapper {
+    /// clock 环,按换入顺序记录常驻用户页
+    ring: VecDeque<ClockEntry>,
+    /// 交换区:槽号 -> 页内容
+    slots: BTreeMap<usize, Vec<u8>>,
+    /// 下一个从未使用过的槽号
+    next_slot: usize,
+    /// 换回后回收、可重复使用的槽号
+    free_slots: Vec<usize>,
+}
+
+impl Swapper {
+    pub fn new() -> Self {
+        Self {
+            ring: VecDeque::new(),
+            slots: BTreeMap::new(),
+            next_slot: 0,
+            free_slots: Vec::new(),
+        }
+    }
+    /// 取一个空闲交换槽,优先复用换回时回收的槽号。
+    fn alloc_slot(&mut self) -> usize {
+        if let Some(slot) = self.free_slots.pop() {
+            slot
+        } else {
+            let slot = self.next_slot;
+            self.next_slot += 1;
+            slot
+        }
+    }
+    /// Register a freshly mapped page so the clock can later consider it.
+    pub fn track(&mut self, token: usize, vpn: VirtPageNum) {
+        self.ring.push_back(ClockEntry { token, vpn });
+    }
+    /// Run the clock (second-chance) algorithm over `page_table` and evict one
+    /// victim, returning the freed VPN. Scans the ring: if `A` == 1 clear it and
+    /// give the page a second chance, otherwise select it as the victim.
+    pub fn reclaim_one(&mut self, page_table: &mut PageTable) -> Option<VirtPageNum> {
+        let len = self.ring.len();
+        for _ in 0..len {
+            let entry = self.ring.pop_front()?;
+            let pte = match page_table.translate(entry.vpn) {
+                Some(pte) if pte.is_valid() => pte,
+                // 该页已经不在了(被 unmap),从环里丢弃
+                _ => continue,
+            };
+            if page_table.accessed(entry.vpn) {
+                // 第二次机会:清 A 位,放回环尾
+                page_table.clear_accessed(entry.vpn);
+                self.ring.push_back(entry);
+                continue;
+            }
+            self.evict(page_table, entry.vpn, pte);
+            return Some(entry.vpn);
+        }
+        None
+    }
+    /// Write a dirty victim to the swap area, install the swapped encoding and
+    /// free its frame.
+    fn evict(&mut self, page_table: &mut PageTable, vpn: VirtPageNum, pte: PageTableEntry) {
+        let ppn = pte.ppn();
+        // 无效的“已换出”编码:清掉 V 位(由 PTE_SWAPPED 单独标记“存在但被换出”),
+        // 只保留原权限的 R/W/X/U 等低位。
+        let low = (pte.flags().bits as usize) & !(PTEFlags::V.bits as usize) & 0xff;
+        // 只有脏页才写回交换区并占用槽号;干净页用 PTE_SWAP_ZERO 标记,不占槽。
+        let bits = if page_table.dirty(vpn) {
+            let slot = self.alloc_slot();
+            let mut buf = vec![0u8; PAGE_SIZE];
+            buf.copy_from_slice(ppn.get_bytes_array());
+            self.slots.insert(slot, buf);
+            (slot << 10) | PTE_SWAPPED | low
+        } else {
+            PTE_SWAPPED | PTE_SWAP_ZERO | low
+        };
+        page_table.set_pte(vpn, PageTableEntry { bits });
+        frame_dealloc(ppn);
+    }
+    /// On a fault for a swapped PTE, allocate a frame, read the slot back (or
+    /// zero-fill a clean page that was never written to swap) and restore the
+    /// mapping. Returns the freshly allocated [`FrameTracker`] so the owning
+    /// `MapArea` can take ownership of it; returns `None` when `vpn` was not a
+    /// swapped page.
+    pub fn swap_in(
+        &mut self,
+        page_table: &mut PageTable,
+        vpn: VirtPageNum,
+    ) -> Option<FrameTracker> {
+        // 必须用 translate_raw 读原始条目:换出编码清掉了 V 位,translate 会
+        // 把它当作无效返回 None,导致换出的页永远无法换回。
+        let pte = page_table.translate_raw(vpn)?;
+        if pte.is_valid() || pte.bits & PTE_SWAPPED == 0 {
+            return None;
+        }
+        let frame = frame_alloc().unwrap();
+        let ppn: PhysPageNum = frame.ppn;
+        if pte.bits & PTE_SWAP_ZERO != 0 {
+            // 干净页:没有后备数据,直接给一张清零页
+            ppn.get_bytes_array().fill(0);
+        } else {
+            let slot = pte.bits >> 10;
+            let buf = self.slots.remove(&slot).unwrap();
+            ppn.get_bytes_array().copy_from_slice(&buf);
+            // 槽号回收,供后续换出复用
+            self.free_slots.push(slot);
+        }
+        let flags = PTEFlags::from_bits((pte.bits & 0xff) as u8).unwrap() | PTEFlags::V;
+        page_table.set_pte(vpn, PageTableEntry::new(ppn, flags));
+        self.track(page_table.token(), vpn);
+        // 把帧交还给调用方,由其插入 MapArea.data_frames 持有所有权
+        Some(frame)
+    }
+}
+
+/// Periodic entry point: evict up to `target` pages with the clock scanner,
+/// stopping early when the ring yields no victim. The number of frames that
+/// are already free is not consulted.
+#[allow(unused)]
+pub fn reclaim_if_low(page_table: &mut PageTable, mut target: usize) {
+    let mut swapper = SWAPPER.exclusive_access();
+    while target > 0 {
+        match swapper.reclaim_one(page_table) {
+            Some(_) => target -= 1,
+            None => break,
+        }
+    }
+}
+
+/// Track a newly mapped user page for future reclamation.
+pub fn track_page(token: usize, vpn: VirtPageNum) {
+    SWAPPER.exclusive_access().track(token, vpn);
+}
+
+/// Attempt to service a fault by swapping the page back in, returning the
+/// restored frame for the owning `MapArea` to take ownership of.
+pub fn try_swap_in(page_table: &mut PageTable, vpn: VirtPageNum) -> Option<FrameTracker> {
+    SWAPPER.exclusive_access().swap_in(page_table, vpn)
+}
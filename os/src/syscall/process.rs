@@ -4,7 +4,7 @@ use core::mem::size_of;
 
 use crate::{
     config::MAX_SYSCALL_NUM,
-    mm::translated_byte_buffer,
+    mm::{translated_byte_buffer_checked, PTEFlags, UserBuffer},
     task::{
         change_program_brk, current_user_token, exit_current_and_run_next, get_current_task_info,
         mmap, munmap, suspend_current_and_run_next, TaskStatus,
@@ -51,20 +51,26 @@ pub fn sys_get_time(ts: *mut TimeVal, _tz: usize) -> isize {
     trace!("kernel: sys_get_time");
     //获取的就是多个切片的引用
     //获取的是物理地址的引用
-    let buffers =
-        translated_byte_buffer(current_user_token(), ts as *const u8, size_of::<TimeVal>());
+    //目标必须是用户可写的已映射内存,否则返回 -1 而不是 panic
+    let buffers = match translated_byte_buffer_checked(
+        current_user_token(),
+        ts as *const u8,
+        size_of::<TimeVal>(),
+        PTEFlags::W,
+    ) {
+        Ok(buffers) => buffers,
+        Err(err) => return err,
+    };
     let us = get_time_us();
     let time_val = TimeVal {
         sec: us / 1_000_000,
         usec: us % 1_000_000,
     };
-    let mut time_val_ptr = &time_val as *const _ as *const u8;
-    for buffer in buffers {
-        unsafe {
-            time_val_ptr.copy_to(buffer.as_mut_ptr(), buffer.len());
-            time_val_ptr = time_val_ptr.add(buffer.len());
-        }
-    }
+    // 把 TimeVal 序列化后通过 UserBuffer 跨页写入用户空间
+    let bytes = unsafe {
+        core::slice::from_raw_parts(&time_val as *const _ as *const u8, size_of::<TimeVal>())
+    };
+    UserBuffer::new(buffers).write(bytes);
     0
 }
 
@@ -73,8 +79,15 @@ pub fn sys_task_info(ti: *mut TaskInfo) -> isize {
     trace!("kernel: sys_task_info");
     //获取的就是多个切片的引用
     //获取的是物理地址的引用
-    let buffers =
-        translated_byte_buffer(current_user_token(), ti as *const u8, size_of::<TaskInfo>());
+    let buffers = match translated_byte_buffer_checked(
+        current_user_token(),
+        ti as *const u8,
+        size_of::<TaskInfo>(),
+        PTEFlags::W,
+    ) {
+        Ok(buffers) => buffers,
+        Err(err) => return err,
+    };
     let task_info = get_current_task_info();
     // trace!("task_info {:?}", task_info.2);
     // println!("task_info 0 {}", task_info.0.);
@@ -88,13 +101,10 @@ pub fn sys_task_info(ti: *mut TaskInfo) -> isize {
         syscall_times: task_info.1,
         time: task_info.2,
     };
-    let mut task_info_ptr = &task_info as *const _ as *const u8;
-    for buffer in buffers {
-        unsafe {
-            task_info_ptr.copy_to(buffer.as_mut_ptr(), buffer.len());
-            task_info_ptr = task_info_ptr.add(buffer.len());
-        }
-    }
+    let bytes = unsafe {
+        core::slice::from_raw_parts(&task_info as *const _ as *const u8, size_of::<TaskInfo>())
+    };
+    UserBuffer::new(buffers).write(bytes);
     // unsafe {
     //     *ti = TaskInfo {
     //         status: task_info.0,